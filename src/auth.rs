@@ -0,0 +1,93 @@
+use axum::extract::{Query, State};
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use axum::Router;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{oneshot, Mutex};
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+struct CallbackState {
+    expected_state: String,
+    result: Mutex<Option<oneshot::Sender<Result<String, String>>>>,
+}
+
+async fn callback_handler(
+    Query(query): Query<CallbackQuery>,
+    State(state): State<Arc<CallbackState>>,
+) -> impl IntoResponse {
+    let result = if let Some(error) = query.error {
+        Err(format!("ESI denied the authorization request: {error}"))
+    } else {
+        match (query.code, query.state) {
+            (Some(code), Some(received_state)) if received_state == state.expected_state => {
+                Ok(code)
+            }
+            (Some(_), Some(_)) => Err("OAuth state mismatch; possible CSRF attempt".to_string()),
+            _ => Err("Callback is missing the code or state parameter".to_string()),
+        }
+    };
+
+    let page = match &result {
+        Ok(_) => "<html><body>Authenticated, you may close this window.</body></html>",
+        Err(_) => "<html><body>Authentication failed, see the toons logs.</body></html>",
+    };
+
+    if let Some(sender) = state.result.lock().await.take() {
+        let _ = sender.send(result);
+    }
+    Html(page)
+}
+
+/// Waits for a single ESI OAuth callback on `callback_url`'s host/port,
+/// validating `expected_state` to guard against CSRF. Resolves with an
+/// error if Ctrl-C is pressed first, so a cancelled auth flow releases the
+/// port instead of leaving it bound.
+pub async fn await_callback(callback_url: &str, expected_state: &str) -> Result<String, String> {
+    let addr = callback_addr(callback_url).await?;
+    let (tx, rx) = oneshot::channel();
+    let state = Arc::new(CallbackState {
+        expected_state: expected_state.to_string(),
+        result: Mutex::new(Some(tx)),
+    });
+
+    let app = Router::new()
+        .route("/esi/callback", get(callback_handler))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind {addr}: {e}"))?;
+
+    tokio::select! {
+        result = axum::serve(listener, app) => {
+            result.map_err(|e| format!("Callback server error: {e}"))?;
+            Err("Callback server exited before receiving a callback".to_string())
+        }
+        _ = tokio::signal::ctrl_c() => {
+            Err("Interrupted before receiving the OAuth callback".to_string())
+        }
+        received = rx => received.map_err(|_| "Callback sender dropped".to_string())?,
+    }
+}
+
+/// Resolves `callback_url`'s host/port to a bindable address. Uses DNS
+/// resolution rather than `SocketAddr::parse` so hostnames like `localhost`
+/// (as in `http://localhost:5000/esi/callback`) work, not just numeric IPs.
+async fn callback_addr(callback_url: &str) -> Result<SocketAddr, String> {
+    let url = url::Url::parse(callback_url).map_err(|e| format!("Invalid callback_url: {e}"))?;
+    let host = url.host_str().unwrap_or("127.0.0.1");
+    let port = url.port_or_known_default().unwrap_or(80);
+    tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve callback host '{host}:{port}': {e}"))?
+        .next()
+        .ok_or_else(|| format!("No addresses found for callback host '{host}:{port}'"))
+}
@@ -0,0 +1,81 @@
+use figment::providers::{Env, Format, Toml};
+use figment::Figment;
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "toons.toml";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EsiConfig {
+    pub client_id: String,
+    pub secret: String,
+    pub callback_url: String,
+    pub scopes: Vec<String>,
+}
+
+impl EsiConfig {
+    pub fn scope_string(&self) -> String {
+        self.scopes.join(" ")
+    }
+}
+
+/// A named, user-configured set of skills to track, e.g. extractor skills
+/// or injector thresholds. `sp_per_unit` is the amount of skillpoints one
+/// "unit" (an extraction, an injector, ...) is worth for this group.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SkillGroup {
+    pub name: String,
+    pub skill_ids: Vec<i32>,
+    pub sp_per_unit: i64,
+}
+
+/// Controls the optional OpenTelemetry OTLP exporter. Disabled by default,
+/// so a bare `toons.toml` still only logs to the terminal.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct OtelConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://localhost:4317".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    pub esi: EsiConfig,
+    #[serde(default, rename = "group")]
+    pub groups: Vec<SkillGroup>,
+    #[serde(default)]
+    pub otel: OtelConfig,
+}
+
+impl Config {
+    /// Loads `toons.toml`, overlaid with `TOONS_`-prefixed environment
+    /// variables (e.g. `TOONS_ESI__CLIENT_ID` for `esi.client_id`).
+    pub fn load() -> Result<Self, figment::Error> {
+        Figment::new()
+            .merge(Toml::file(CONFIG_FILE))
+            .merge(Env::prefixed("TOONS_").split("__"))
+            .extract()
+    }
+
+    /// Resolves the skill group to use for a stats run: the one named
+    /// `name` if given, the sole configured group if there's exactly one,
+    /// or an error if the choice is ambiguous.
+    pub fn resolve_group(&self, name: Option<&str>) -> Result<&SkillGroup, String> {
+        if let Some(name) = name {
+            return self
+                .groups
+                .iter()
+                .find(|g| g.name == name)
+                .ok_or_else(|| format!("No skill group named '{name}' configured"));
+        }
+        match self.groups.as_slice() {
+            [group] => Ok(group),
+            [] => Err("No skill groups configured in toons.toml".to_string()),
+            _ => Err("Multiple skill groups configured; specify one with --group".to_string()),
+        }
+    }
+}
@@ -1,17 +1,29 @@
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
 use chrono::prelude::*;
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
 use dotenv::dotenv;
 use futures::future::join_all;
-use log::{debug, error, info};
 use rfesi::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::TcpListener;
-
-const TOONS_FILE: &str = "toons.json";
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info, instrument};
+
+mod auth;
+mod config;
+mod metrics;
+mod store;
+mod telemetry;
+mod vault;
+
+use config::Config;
+use metrics::Metrics;
+use store::CharacterData;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -29,21 +41,23 @@ enum Commands {
     Show { name: String },
     Auth,
     Refresh { name: String },
-    Stats { name: Option<String> },
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
-struct CharacterData {
-    name: String,
-    id: i32,
-    refresh_token: String,
-    scopes: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct EsiCallbackParams {
-    code: String,
-    state: String,
+    Stats {
+        name: Option<String>,
+        /// Name of the configured skill group to report on.
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Re-encrypt every stored refresh token under a new vault passphrase.
+    Rekey,
+    /// Run as a daemon, periodically refreshing stats and exposing them to
+    /// Prometheus at `/metrics`.
+    Serve {
+        #[arg(long, default_value_t = 300)]
+        interval: u64,
+        /// Name of the configured skill group to report on.
+        #[arg(long)]
+        group: Option<String>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,91 +69,33 @@ struct EsiVerifyResponse {
     scopes: String,
 }
 
-fn create_esi() -> EsiResult<Esi> {
-    let client_id = std::env::var("ESI_CLIENT_ID").expect("ESI_CLIENT_ID must be set.");
-    let secret = std::env::var("ESI_SECRET").expect("ESI_CLIENT_SECRET must be set.");
+fn create_esi(config: &config::EsiConfig) -> EsiResult<Esi> {
     EsiBuilder::new()
         .user_agent("eve-toons-agent")
-        .client_id(&client_id)
-        .client_secret(&secret)
-        .callback_url("http://localhost:5000/esi/callback")
-        .scope("esi-characterstats.read.v1 esi-skills.read_skills.v1 esi-skills.read_skillqueue.v1")
+        .client_id(&config.client_id)
+        .client_secret(&config.secret)
+        .callback_url(&config.callback_url)
+        .scope(&config.scope_string())
         .build()
 }
 
-fn write_toons(toons: &HashMap<String, CharacterData>) {
-    let toon_file = File::create(TOONS_FILE).expect("Create file");
-    let mut writer = BufWriter::new(toon_file);
-    serde_json::to_writer_pretty(&mut writer, &toons).expect("Serialize");
-    writer.flush().expect("Flush writer");
-}
+async fn do_auth(config: &Config) {
+    let conn = store::open_db().expect("Open toons database");
+    let vault = vault::Vault::open(&conn).expect("Open vault");
 
-fn read_toons() -> HashMap<String, CharacterData> {
-    let file = match File::open(TOONS_FILE) {
-        Ok(file) => file,
-        Err(_) => {
-            let ret: HashMap<String, CharacterData> = HashMap::new();
-            return ret;
-        }
-    };
-    let reader = BufReader::new(file);
-    serde_json::from_reader(reader).unwrap()
-}
+    loop {
+        let mut esi = create_esi(&config.esi).unwrap();
+        let authorize = esi.get_authorize_url().unwrap();
+        println!("Authenticating. {}", authorize.authorization_url);
 
-fn auth_cb() -> Option<EsiCallbackParams> {
-    let mut params = None;
-    let listener = TcpListener::bind("127.0.0.1:5000").unwrap();
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let mut line = String::new();
-                let mut reader = BufReader::new(stream.try_clone().unwrap());
-                loop {
-                    match reader.read_line(&mut line) {
-                        Ok(_) => {
-                            if line.starts_with("GET /esi/callback?") {
-                                let (query, _) = line
-                                    .strip_prefix("GET /esi/callback?")
-                                    .unwrap()
-                                    .split_once(' ')
-                                    .unwrap();
-                                params = Some(serde_qs::from_str(query).unwrap());
-                            }
-                            if line == "\r\n" {
-                                break;
-                            }
-                            line.clear()
-                        }
-                        Err(e) => {
-                            error!("Encountered IO error: {}", e);
-                        }
-                    }
-                }
-                let response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=UTF-8\r\n\r\n<html><body>OK</body></html>\r\n";
-                match stream.write(response) {
-                    Ok(_) => (),
-                    Err(e) => error!("Failed sending response: {}", e),
-                }
-                break;
-            }
+        let code = match auth::await_callback(&config.esi.callback_url, &authorize.state).await {
+            Ok(code) => code,
             Err(e) => {
-                error!("Unable to accept: {e}");
+                error!("Auth callback failed: {e}");
+                break;
             }
-        }
-    }
-    params
-}
-
-async fn do_auth() {
-    let mut toons = read_toons();
-
-    loop {
-        let mut esi = create_esi().unwrap();
-        let url = esi.get_authorize_url().unwrap().authorization_url;
-        println!("Authenticating. {}", url);
-
-        let params = auth_cb().expect("Auth callback failed");
-        let _ = esi.authenticate(&params.code, None).await;
+        };
+        let _ = esi.authenticate(&code, None).await;
         let client = reqwest::Client::new();
         let request_url = "https://login.eveonline.com/oauth/verify";
         let response = client
@@ -153,41 +109,21 @@ async fn do_auth() {
             .unwrap();
         let verify: EsiVerifyResponse = serde_json::from_str(&response).unwrap();
         debug!("{:#?}", verify);
-        toons.insert(
-            verify.character_name.clone(),
-            CharacterData {
-                name: verify.character_name,
-                id: verify.character_id,
-                refresh_token: esi.refresh_token.clone().unwrap(),
-                scopes: verify.scopes,
-            },
-        );
-        write_toons(&toons);
-    }
-}
-
-fn find_toon<'a>(
-    toons: &'a HashMap<String, CharacterData>,
-    name: &str,
-) -> Option<&'a CharacterData> {
-    match toons.get(name) {
-        Some(toon) => {
-            return Some(toon);
-        }
-        None => {
-            for (toon_name, toon_data) in toons {
-                if toon_name.starts_with(name) {
-                    return Some(toon_data);
-                }
-            }
-        }
+        let data = CharacterData {
+            name: verify.character_name,
+            id: verify.character_id,
+            refresh_token: vault
+                .encrypt(&esi.refresh_token.clone().unwrap())
+                .expect("Encrypt refresh token"),
+            scopes: verify.scopes,
+        };
+        store::upsert_character(&conn, &data).expect("Persist character");
     }
-    None
 }
 
 fn do_show(name: &str) {
-    let toons = read_toons();
-    match find_toon(&toons, name) {
+    let conn = store::open_db().expect("Open toons database");
+    match store::find_by_prefix(&conn, name).expect("Query character") {
         Some(toon) => {
             println!("{toon:#?}");
         }
@@ -197,16 +133,24 @@ fn do_show(name: &str) {
     }
 }
 
-async fn do_refresh(name: &str) {
-    let toons = read_toons();
-    match find_toon(&toons, name) {
+async fn do_refresh(name: &str, config: &Config) {
+    let conn = store::open_db().expect("Open toons database");
+    match store::find_by_prefix(&conn, name).expect("Query character") {
         Some(toon) => {
             println!("{toon:#?}");
-            let mut esi = create_esi().unwrap();
-            println!("{:#?}", esi);
-            let result = esi.refresh_access_token(Some(&toon.refresh_token)).await;
-            println!("{:#?}", result);
-            println!("{:#?}", esi);
+            let vault = vault::Vault::open(&conn).expect("Open vault");
+            let refresh_token = match vault.decrypt(&toon.refresh_token) {
+                Ok(refresh_token) => refresh_token,
+                Err(e) => {
+                    println!("Failed to decrypt refresh token: {e}");
+                    return;
+                }
+            };
+            let mut esi = create_esi(&config.esi).unwrap();
+            match esi.refresh_access_token(Some(&refresh_token)).await {
+                Ok(_) => println!("Token refreshed successfully"),
+                Err(e) => println!("Failed to refresh token: {e}"),
+            }
         }
         None => {
             println!("No Character '{name}' found");
@@ -214,14 +158,22 @@ async fn do_refresh(name: &str) {
     }
 }
 
-static CROP_SKILLS: [i32; 7] = [3412, 3551, 13278, 21718, 25739, 25810, 25811];
+async fn do_rekey() {
+    let mut conn = store::open_db().expect("Open toons database");
+    let old_passphrase =
+        rpassword::prompt_password("Current vault passphrase: ").expect("Read passphrase");
+    let new_passphrase =
+        rpassword::prompt_password("New vault passphrase: ").expect("Read passphrase");
+    vault::rekey(&mut conn, &old_passphrase, &new_passphrase).expect("Re-key vault");
+    println!("Vault re-keyed.");
+}
 
 #[derive(Debug)]
-struct CropStat {
-    name: String,
-    points: i64,
-    training: bool,
-    queued: u32,
+pub(crate) struct CropStat {
+    pub(crate) name: String,
+    pub(crate) points: i64,
+    pub(crate) training: bool,
+    pub(crate) queued: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -236,8 +188,8 @@ struct QueuedSkill {
     level_end_sp: i32,
 }
 
-fn calculate_queued_skill(skill: &QueuedSkill) -> (bool, bool, i64) {
-    if !CROP_SKILLS.contains(&skill.skill_id) {
+fn calculate_queued_skill(skill: &QueuedSkill, skill_ids: &[i32]) -> (bool, bool, i64) {
+    if !skill_ids.contains(&skill.skill_id) {
         return (false, false, 0);
     }
     debug!("{:#?}", skill);
@@ -281,24 +233,33 @@ fn calculate_queued_skill(skill: &QueuedSkill) -> (bool, bool, i64) {
     (training, is_crop, points)
 }
 
-async fn do_stats_single(data: CharacterData) -> Result<CropStat, String> {
+#[instrument(skip(data, vault, config, group), fields(character = %data.name, character_id = data.id))]
+async fn do_stats_single(
+    data: CharacterData,
+    vault: Arc<vault::Vault>,
+    config: &Config,
+    group: &config::SkillGroup,
+) -> Result<CropStat, String> {
     let mut stat = CropStat {
         name: data.name.clone(),
         points: 0,
         training: false,
         queued: 0,
     };
-    let mut esi = create_esi().unwrap();
+    let mut esi = create_esi(&config.esi).unwrap();
 
     info!("Refreshing API token for {}", data.name);
-    let _ = esi.refresh_access_token(Some(&data.refresh_token)).await;
+    let refresh_token = vault
+        .decrypt(&data.refresh_token)
+        .map_err(|e| format!("Error decrypting refresh token: {e}"))?;
+    let _ = esi.refresh_access_token(Some(&refresh_token)).await;
     let _ = esi.update_spec().await;
 
     info!("Pulling skills for {}", data.name);
     match esi.group_skills().get_skills(data.id).await {
         Ok(skills) => {
             for skill in skills.skills {
-                if CROP_SKILLS.contains(&skill.skill_id) {
+                if group.skill_ids.contains(&skill.skill_id) {
                     debug!("{:#?}", skill);
                     stat.points += skill.skillpoints_in_skill;
                 }
@@ -320,7 +281,7 @@ async fn do_stats_single(data: CharacterData) -> Result<CropStat, String> {
     match queue {
         Ok(queue) => {
             for skill in queue {
-                let (training, is_crop, points) = calculate_queued_skill(&skill);
+                let (training, is_crop, points) = calculate_queued_skill(&skill, &group.skill_ids);
                 if training {
                     stat.training = true;
                 }
@@ -335,22 +296,29 @@ async fn do_stats_single(data: CharacterData) -> Result<CropStat, String> {
     Ok(stat)
 }
 
-async fn do_stats(single_name: &Option<String>) {
+/// Fetches `group`'s stats for `single_name`, or every stored character if
+/// `None`, sorted by points descending.
+async fn collect_stats(
+    conn: &rusqlite::Connection,
+    vault: Arc<vault::Vault>,
+    config: &Config,
+    group: &config::SkillGroup,
+    single_name: Option<&str>,
+) -> Vec<CropStat> {
     let mut crop_stats = Vec::new();
     let mut single = Vec::new();
-    let toons = read_toons();
 
     if let Some(single_name) = single_name {
         // Single character stats
-        if let Some(data) = find_toon(&toons, single_name) {
-            single.push(do_stats_single(data.clone()));
+        if let Some(data) = store::find_by_prefix(conn, single_name).expect("Query character") {
+            single.push(do_stats_single(data, vault.clone(), config, group));
         } else {
             error!("Could not find Character: {}", single_name);
         }
     } else {
         // All character stats
-        for (_, data) in toons {
-            single.push(do_stats_single(data.clone()));
+        for data in store::list_characters(conn).expect("List characters") {
+            single.push(do_stats_single(data, vault.clone(), config, group));
         }
     }
     for result in join_all(single).await {
@@ -365,16 +333,31 @@ async fn do_stats(single_name: &Option<String>) {
     }
     crop_stats.sort_by_key(|s| s.points);
     crop_stats.reverse();
-    println!("--- Results ---");
+    crop_stats
+}
+
+async fn do_stats(single_name: &Option<String>, group_name: &Option<String>, config: &Config) {
+    let group = match config.resolve_group(group_name.as_deref()) {
+        Ok(group) => group,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    let conn = store::open_db().expect("Open toons database");
+    let vault = Arc::new(vault::Vault::open(&conn).expect("Open vault"));
+    let crop_stats = collect_stats(&conn, vault, config, group, single_name.as_deref()).await;
+
+    println!("--- Results for group '{}' ---", group.name);
     let mut available_extracts = 0;
     for stat in crop_stats {
-        available_extracts += stat.points / 500_000;
+        available_extracts += stat.points / group.sp_per_unit;
         let training = if stat.training { 1 } else { 0 };
         println!(
             "{}: {} points, {:.2} extractions, {} crop skill training, {} crop skills queued",
             stat.name,
             stat.points,
-            stat.points as f64 / 500_000.0,
+            stat.points as f64 / group.sp_per_unit as f64,
             training,
             stat.queued,
         );
@@ -383,19 +366,73 @@ async fn do_stats(single_name: &Option<String>) {
     println!("Total available extractions: {}", available_extracts);
 }
 
+const METRICS_ADDR: &str = "0.0.0.0:9186";
+
+async fn serve_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    ([("Content-Type", "text/plain; version=0.0.4")], metrics.encode())
+}
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c().await.ok();
+    info!("Shutting down");
+}
+
+/// Runs as a daemon: every `interval` seconds, refreshes `group`'s stats
+/// for every stored character and republishes them as Prometheus gauges
+/// served at `http://METRICS_ADDR/metrics`, until Ctrl-C is pressed.
+async fn do_serve(interval_secs: u64, group_name: &Option<String>, config: Config) {
+    let group = match config.resolve_group(group_name.as_deref()) {
+        Ok(group) => group.clone(),
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    let conn = store::open_db().expect("Open toons database");
+    let vault = Arc::new(vault::Vault::open(&conn).expect("Open vault"));
+    let metrics = Arc::new(Metrics::new().expect("Create metrics registry"));
+
+    let refresh_metrics = metrics.clone();
+    let ticker_handle = tokio::spawn(async move {
+        let mut ticker = time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            info!("Refreshing stats for metrics export");
+            for stat in collect_stats(&conn, vault.clone(), &config, &group, None).await {
+                refresh_metrics.update(&stat, group.sp_per_unit);
+            }
+        }
+    });
+
+    let addr: SocketAddr = METRICS_ADDR.parse().expect("Valid metrics address");
+    let app = Router::new()
+        .route("/metrics", get(serve_metrics))
+        .with_state(metrics);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .expect("Bind metrics listener");
+    info!("Serving metrics on http://{addr}/metrics");
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+    {
+        error!("Metrics server error: {e}");
+    }
+    ticker_handle.abort();
+}
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     let cli = Cli::parse();
 
-    env_logger::Builder::new()
-        .filter_level(cli.verbose.log_level_filter())
-        .init();
+    let otel = Config::load().map(|c| c.otel).unwrap_or_default();
+    telemetry::init(&cli.verbose, &otel);
 
     match &cli.command {
         Some(Commands::List) => {
-            let toons = read_toons();
-            for (_, data) in toons {
+            let conn = store::open_db().expect("Open toons database");
+            for data in store::list_characters(&conn).expect("List characters") {
                 println!("{} :: {}", data.name, data.id);
             }
         }
@@ -403,13 +440,23 @@ async fn main() -> std::io::Result<()> {
             do_show(name);
         }
         Some(Commands::Auth) => {
-            do_auth().await;
+            let config = Config::load().expect("Load toons.toml");
+            do_auth(&config).await;
         }
         Some(Commands::Refresh { name }) => {
-            do_refresh(name).await;
+            let config = Config::load().expect("Load toons.toml");
+            do_refresh(name, &config).await;
+        }
+        Some(Commands::Stats { name, group }) => {
+            let config = Config::load().expect("Load toons.toml");
+            do_stats(name, group, &config).await;
+        }
+        Some(Commands::Rekey) => {
+            do_rekey().await;
         }
-        Some(Commands::Stats { name }) => {
-            do_stats(name).await;
+        Some(Commands::Serve { interval, group }) => {
+            let config = Config::load().expect("Load toons.toml");
+            do_serve(*interval, group, config).await;
         }
         None => {}
     }
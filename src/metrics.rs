@@ -0,0 +1,81 @@
+use prometheus::{Encoder, GaugeVec, Opts, Registry, TextEncoder};
+
+use crate::CropStat;
+
+/// Holds the Prometheus gauges tracked by the `serve` subcommand, one value
+/// per character, refreshed on the configured interval.
+pub struct Metrics {
+    registry: Registry,
+    skill_points: GaugeVec,
+    available_extractions: GaugeVec,
+    crop_skill_training: GaugeVec,
+    crop_skills_queued: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+
+        let skill_points = GaugeVec::new(
+            Opts::new("toons_skill_points", "Crop skill points trained"),
+            &["character"],
+        )?;
+        let available_extractions = GaugeVec::new(
+            Opts::new(
+                "toons_available_extractions",
+                "Units available at the tracked group's configured sp_per_unit rate",
+            ),
+            &["character"],
+        )?;
+        let crop_skill_training = GaugeVec::new(
+            Opts::new(
+                "toons_crop_skill_training",
+                "Whether a crop skill is currently training (1/0)",
+            ),
+            &["character"],
+        )?;
+        let crop_skills_queued = GaugeVec::new(
+            Opts::new("toons_crop_skills_queued", "Crop skills waiting in the queue"),
+            &["character"],
+        )?;
+
+        registry.register(Box::new(skill_points.clone()))?;
+        registry.register(Box::new(available_extractions.clone()))?;
+        registry.register(Box::new(crop_skill_training.clone()))?;
+        registry.register(Box::new(crop_skills_queued.clone()))?;
+
+        Ok(Self {
+            registry,
+            skill_points,
+            available_extractions,
+            crop_skill_training,
+            crop_skills_queued,
+        })
+    }
+
+    pub fn update(&self, stat: &CropStat, sp_per_unit: i64) {
+        self.skill_points
+            .with_label_values(&[&stat.name])
+            .set(stat.points as f64);
+        self.available_extractions
+            .with_label_values(&[&stat.name])
+            .set(stat.points as f64 / sp_per_unit as f64);
+        self.crop_skill_training
+            .with_label_values(&[&stat.name])
+            .set(if stat.training { 1.0 } else { 0.0 });
+        self.crop_skills_queued
+            .with_label_values(&[&stat.name])
+            .set(stat.queued as f64);
+    }
+
+    /// Renders all registered gauges in the Prometheus text exposition
+    /// format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let encoder = TextEncoder::new();
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encode metrics");
+        buffer
+    }
+}
@@ -0,0 +1,129 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+const DB_FILE: &str = "toons.db";
+
+/// Ordered list of migrations applied in order to bring a fresh or existing
+/// database up to the current schema. Each entry is run once, inside its own
+/// transaction, and recorded in `schema_version`.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE characters (
+        id            INTEGER PRIMARY KEY,
+        name          TEXT NOT NULL,
+        refresh_token TEXT NOT NULL,
+        scopes        TEXT NOT NULL
+    );
+    CREATE UNIQUE INDEX characters_name_idx ON characters (name);
+",
+    "
+    CREATE TABLE vault (
+        id         INTEGER PRIMARY KEY CHECK (id = 1),
+        salt       BLOB NOT NULL,
+        argon2_mem INTEGER NOT NULL,
+        argon2_time INTEGER NOT NULL,
+        argon2_lanes INTEGER NOT NULL
+    );
+",
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CharacterData {
+    pub name: String,
+    pub id: i32,
+    pub refresh_token: String,
+    pub scopes: String,
+}
+
+/// Opens the toons database, creating it and running any pending migrations
+/// if necessary.
+pub fn open_db() -> rusqlite::Result<Connection> {
+    let conn = Connection::open(DB_FILE)?;
+    run_migrations(&conn)?;
+    Ok(conn)
+}
+
+fn run_migrations(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        [],
+    )?;
+    let current: i64 = conn
+        .query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| {
+            row.get(0)
+        })?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate() {
+        let version = (i + 1) as i64;
+        if version <= current {
+            continue;
+        }
+        tracing::debug!("Applying migration {version}");
+        conn.execute_batch(&format!(
+            "BEGIN; {migration} INSERT INTO schema_version (version) VALUES ({version}); COMMIT;"
+        ))?;
+    }
+    Ok(())
+}
+
+pub fn upsert_character(conn: &Connection, data: &CharacterData) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO characters (id, name, refresh_token, scopes) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name,
+            refresh_token = excluded.refresh_token,
+            scopes = excluded.scopes",
+        params![data.id, data.name, data.refresh_token, data.scopes],
+    )?;
+    Ok(())
+}
+
+pub fn get_character(conn: &Connection, name: &str) -> rusqlite::Result<Option<CharacterData>> {
+    conn.query_row(
+        "SELECT name, id, refresh_token, scopes FROM characters WHERE name = ?1",
+        params![name],
+        |row| {
+            Ok(CharacterData {
+                name: row.get(0)?,
+                id: row.get(1)?,
+                refresh_token: row.get(2)?,
+                scopes: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+}
+
+pub fn list_characters(conn: &Connection) -> rusqlite::Result<Vec<CharacterData>> {
+    let mut stmt = conn.prepare("SELECT name, id, refresh_token, scopes FROM characters")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(CharacterData {
+            name: row.get(0)?,
+            id: row.get(1)?,
+            refresh_token: row.get(2)?,
+            scopes: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Looks up a character by exact name, falling back to the first character
+/// whose name starts with `prefix`.
+pub fn find_by_prefix(conn: &Connection, prefix: &str) -> rusqlite::Result<Option<CharacterData>> {
+    if let Some(toon) = get_character(conn, prefix)? {
+        return Ok(Some(toon));
+    }
+    conn.query_row(
+        "SELECT name, id, refresh_token, scopes FROM characters WHERE substr(name, 1, length(?1)) = ?1 LIMIT 1",
+        params![prefix],
+        |row| {
+            Ok(CharacterData {
+                name: row.get(0)?,
+                id: row.get(1)?,
+                refresh_token: row.get(2)?,
+                scopes: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+}
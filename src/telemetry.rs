@@ -0,0 +1,52 @@
+use clap_verbosity_flag::Verbosity;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry};
+
+use crate::config::OtelConfig;
+
+fn level_filter(verbose: &Verbosity) -> tracing::level_filters::LevelFilter {
+    match verbose.log_level_filter() {
+        log::LevelFilter::Off => tracing::level_filters::LevelFilter::OFF,
+        log::LevelFilter::Error => tracing::level_filters::LevelFilter::ERROR,
+        log::LevelFilter::Warn => tracing::level_filters::LevelFilter::WARN,
+        log::LevelFilter::Info => tracing::level_filters::LevelFilter::INFO,
+        log::LevelFilter::Debug => tracing::level_filters::LevelFilter::DEBUG,
+        log::LevelFilter::Trace => tracing::level_filters::LevelFilter::TRACE,
+    }
+}
+
+/// Initializes the global tracing subscriber: a terminal formatter at the
+/// requested verbosity, plus an OTLP exporter when `otel.enabled`, so the
+/// per-character spans in `do_stats_single` show up in a trace backend
+/// instead of just interleaved in the CLI output.
+pub fn init(verbose: &Verbosity, otel: &OtelConfig) {
+    let env_filter = EnvFilter::builder()
+        .with_default_directive(level_filter(verbose).into())
+        .from_env_lossy();
+    let fmt_layer = tracing_subscriber::fmt::layer();
+
+    if otel.enabled {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(&otel.endpoint)
+            .build()
+            .expect("Build OTLP exporter");
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            .build();
+        let tracer = provider.tracer("toons");
+        opentelemetry::global::set_tracer_provider(provider);
+        let otel_layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        Registry::default()
+            .with(env_filter)
+            .with(fmt_layer)
+            .with(otel_layer)
+            .init();
+    } else {
+        Registry::default().with(env_filter).with(fmt_layer).init();
+    }
+}
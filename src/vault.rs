@@ -0,0 +1,172 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use rusqlite::{params, Connection};
+
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+// OWASP-recommended Argon2id baseline for interactive use.
+const ARGON2_MEM_KIB: u32 = 19456;
+const ARGON2_TIME: u32 = 2;
+const ARGON2_LANES: u32 = 1;
+
+struct VaultParams {
+    salt: Vec<u8>,
+    mem_kib: u32,
+    time: u32,
+    lanes: u32,
+}
+
+/// A refresh-token vault keyed by a passphrase-derived Argon2id key. Each
+/// token is encrypted independently with XChaCha20-Poly1305 and a fresh
+/// random nonce; the encoded form stored in the database is `nonce || ct`,
+/// base64-encoded.
+pub struct Vault {
+    cipher: XChaCha20Poly1305,
+}
+
+impl Vault {
+    /// Opens the vault for `conn`, creating the salt/parameter row on first
+    /// use. The passphrase comes from `TOONS_PASSPHRASE` if set, otherwise
+    /// the user is prompted interactively.
+    pub fn open(conn: &Connection) -> rusqlite::Result<Self> {
+        let params = load_or_init_params(conn)?;
+        let passphrase = read_passphrase();
+        Ok(Self::derive(&passphrase, &params))
+    }
+
+    /// Opens the vault with an explicit passphrase, bypassing the env/prompt
+    /// lookup. Used by the re-key flow to unlock with the old passphrase.
+    fn open_with(conn: &Connection, passphrase: &str) -> rusqlite::Result<Self> {
+        let params = load_or_init_params(conn)?;
+        Ok(Self::derive(passphrase, &params))
+    }
+
+    fn derive(passphrase: &str, vault_params: &VaultParams) -> Self {
+        let argon2 = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(vault_params.mem_kib, vault_params.time, vault_params.lanes, Some(32))
+                .expect("Valid Argon2 params"),
+        );
+        let mut key = [0u8; 32];
+        argon2
+            .hash_password_into(passphrase.as_bytes(), &vault_params.salt, &mut key)
+            .expect("Derive vault key");
+        let cipher = XChaCha20Poly1305::new((&key).into());
+        Self { cipher }
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let nonce = XNonce::from_slice(&nonce_bytes);
+        let ciphertext = self
+            .cipher
+            .encrypt(nonce, plaintext.as_bytes())
+            .map_err(|e| format!("Error encrypting refresh token: {e}"))?;
+        let mut payload = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        payload.extend_from_slice(&nonce_bytes);
+        payload.extend_from_slice(&ciphertext);
+        Ok(STANDARD.encode(payload))
+    }
+
+    pub fn decrypt(&self, encoded: &str) -> Result<String, String> {
+        let payload = STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("Error decoding vault payload: {e}"))?;
+        if payload.len() < NONCE_LEN {
+            return Err("Vault payload is shorter than the nonce".to_string());
+        }
+        let (nonce_bytes, ciphertext) = payload.split_at(NONCE_LEN);
+        let nonce = XNonce::from_slice(nonce_bytes);
+        let plaintext = self
+            .cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|e| format!("Error decrypting refresh token: {e}"))?;
+        String::from_utf8(plaintext).map_err(|e| format!("Refresh token is not valid UTF-8: {e}"))
+    }
+}
+
+fn load_or_init_params(conn: &Connection) -> rusqlite::Result<VaultParams> {
+    let existing = conn
+        .query_row(
+            "SELECT salt, argon2_mem, argon2_time, argon2_lanes FROM vault WHERE id = 1",
+            [],
+            |row| {
+                Ok(VaultParams {
+                    salt: row.get(0)?,
+                    mem_kib: row.get(1)?,
+                    time: row.get(2)?,
+                    lanes: row.get(3)?,
+                })
+            },
+        )
+        .ok();
+    if let Some(params) = existing {
+        return Ok(params);
+    }
+
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    conn.execute(
+        "INSERT INTO vault (id, salt, argon2_mem, argon2_time, argon2_lanes) VALUES (1, ?1, ?2, ?3, ?4)",
+        params![salt, ARGON2_MEM_KIB, ARGON2_TIME, ARGON2_LANES],
+    )?;
+    Ok(VaultParams {
+        salt,
+        mem_kib: ARGON2_MEM_KIB,
+        time: ARGON2_TIME,
+        lanes: ARGON2_LANES,
+    })
+}
+
+fn read_passphrase() -> String {
+    if let Ok(passphrase) = std::env::var("TOONS_PASSPHRASE") {
+        return passphrase;
+    }
+    rpassword::prompt_password("Vault passphrase: ").expect("Read passphrase")
+}
+
+/// Re-keys the vault: decrypts every stored refresh token with the old
+/// passphrase, generates a fresh salt, and re-encrypts everything with the
+/// new passphrase. Runs inside a transaction so a failure leaves the
+/// previous vault intact.
+pub fn rekey(conn: &mut Connection, old_passphrase: &str, new_passphrase: &str) -> Result<(), String> {
+    let old_vault = Vault::open_with(conn, old_passphrase).map_err(|e| e.to_string())?;
+
+    let characters = crate::store::list_characters(conn).map_err(|e| e.to_string())?;
+    let mut decrypted = Vec::with_capacity(characters.len());
+    for c in &characters {
+        decrypted.push((c.name.clone(), old_vault.decrypt(&c.refresh_token)?));
+    }
+
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let mut new_salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut new_salt);
+    tx.execute(
+        "UPDATE vault SET salt = ?1, argon2_mem = ?2, argon2_time = ?3, argon2_lanes = ?4 WHERE id = 1",
+        params![new_salt, ARGON2_MEM_KIB, ARGON2_TIME, ARGON2_LANES],
+    )
+    .map_err(|e| e.to_string())?;
+    let new_vault = Vault::derive(
+        new_passphrase,
+        &VaultParams {
+            salt: new_salt,
+            mem_kib: ARGON2_MEM_KIB,
+            time: ARGON2_TIME,
+            lanes: ARGON2_LANES,
+        },
+    );
+    for (name, token) in decrypted {
+        let encrypted = new_vault.encrypt(&token)?;
+        tx.execute(
+            "UPDATE characters SET refresh_token = ?1 WHERE name = ?2",
+            params![encrypted, name],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}